@@ -6,10 +6,15 @@ use fontdb::{Family, Query, Stretch, Style, Weight};
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
 
-use crate::{Attrs, FamilyOwned, Font, FontAttrs};
+use crate::{Attrs, FamilyOwned, Font, FontAttrs, Synthesis};
 
 pub static FONT_SYSTEM: Lazy<FontSystem> = Lazy::new(FontSystem::new);
 
+/// Cap on how many not-yet-parsed faces a single `query_fallback` call will force-parse
+/// while searching for a codepoint. Without this, looking up an uncommon character with
+/// no fallback cache hit yet would walk (and permanently cache) every installed font.
+const MAX_FALLBACK_FACES_TO_PARSE: usize = 32;
+
 #[allow(clippy::missing_errors_doc)]
 pub fn load_font_file<P: AsRef<std::path::Path>>(path: P) -> Result<(), std::io::Error> {
     FONT_SYSTEM.db.write().load_font_file(path)
@@ -21,13 +26,49 @@ pub fn load_font_data(data: Vec<u8>) {
     FONT_SYSTEM.db.write().load_font_data(data);
 }
 
+/// Convert variation axis values to a representation usable as a hash map key.
+///
+/// `f32` isn't `Eq`/`Hash`, but its bit pattern is, so cache keys that need to
+/// distinguish variable-font instances (`FontAttrs`, `CacheKey`) store variations this
+/// way rather than as raw floats.
+fn variations_key(variations: &[([u8; 4], f32)]) -> Vec<([u8; 4], u32)> {
+    variations
+        .iter()
+        .map(|(tag, value)| (*tag, value.to_bits()))
+        .collect()
+}
+
+/// Memory-map a font file and register it as a shared font source, instead of reading
+/// it onto the heap like [`load_font_file`] does.
+///
+/// The OS then pages the file's tables in on demand, which keeps resident memory down
+/// when loading a directory of large CJK or variable fonts whose faces are never all
+/// rasterized. `get_font`/`make_shared_face_data` don't need to change: `Font::new`
+/// already just borrows from whatever shared data `fontdb` is holding for the face,
+/// mapped or not.
+#[cfg(feature = "mmap")]
+#[allow(clippy::missing_errors_doc)]
+pub fn load_font_file_mmap<P: AsRef<std::path::Path>>(path: P) -> Result<(), std::io::Error> {
+    let file = std::fs::File::open(path)?;
+    // Safety: the mapped file must not be mutated or truncated for as long as the
+    // mapping is registered; we never write to font files we load, so this holds as
+    // long as nothing else on the system does either.
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    FONT_SYSTEM
+        .db
+        .write()
+        .load_font_source(fontdb::Source::Binary(Arc::new(mmap)));
+    Ok(())
+}
+
 /// Access system fonts
 pub struct FontSystem {
     locale: String,
     db: RwLock<fontdb::Database>,
     font_cache: RwLock<HashMap<fontdb::ID, Option<Arc<Font>>>>,
     quey_cache: RwLock<HashMap<FontAttrs, Option<fontdb::ID>>>,
-    monospace_cache: RwLock<HashMap<(Style, Weight, Stretch), Option<fontdb::ID>>>,
+    monospace_cache: RwLock<HashMap<(Style, Weight, Stretch, Vec<([u8; 4], u32)>), Option<fontdb::ID>>>,
+    fallback_cache: RwLock<HashMap<(char, FontAttrs), Option<fontdb::ID>>>,
 }
 
 impl FontSystem {
@@ -86,6 +127,7 @@ impl FontSystem {
             font_cache: RwLock::new(HashMap::new()),
             quey_cache: RwLock::new(HashMap::new()),
             monospace_cache: RwLock::new(HashMap::new()),
+            fallback_cache: RwLock::new(HashMap::new()),
         }
     }
 
@@ -128,6 +170,7 @@ impl FontSystem {
             stretch: attrs.stretch,
             style: attrs.style,
             weight: attrs.weight,
+            variations: variations_key(&attrs.variations),
         };
         if let Some(f) = self.quey_cache.read().get(&font_attrs) {
             return *f;
@@ -148,8 +191,43 @@ impl FontSystem {
             })
     }
 
+    /// Work out how much a matched face needs to be faked up to satisfy `attrs`.
+    ///
+    /// `query` picks the closest installed face, but that face may
+    /// still not be bold or italic when that's what was asked for (e.g. a family that
+    /// only ships a regular, upright style). Call this with the matched `id` to get the
+    /// synthetic embolden/skew to bake into the glyph's `CacheKey`.
+    pub fn face_synthesis(&self, id: fontdb::ID, attrs: &Attrs) -> Synthesis {
+        let db = self.db.read();
+        let Some(face) = db.face(id) else {
+            return Synthesis::default();
+        };
+
+        // Weight is on a linear 1..=1000 scale; convert a positive delta to swash's
+        // embolden units the same way desktop compositors derive synthetic bold.
+        let weight_delta = (attrs.weight.0 as f32 - face.weight.0 as f32).max(0.0);
+        let embolden = (weight_delta / 500.0).min(1.0);
+
+        let wants_italic = matches!(attrs.style, Style::Italic | Style::Oblique);
+        // tan(~12 degrees) ~= 0.21, the skew desktop compositors use for faux italics.
+        let skew = if wants_italic && face.style == Style::Normal {
+            0.21
+        } else {
+            0.0
+        };
+
+        Synthesis::from((embolden, skew))
+    }
+
     pub fn query_monospace(&self, attrs: &Attrs) -> Option<fontdb::ID> {
-        let key = (attrs.style, attrs.weight, attrs.stretch);
+        // Keyed on the variations' bit pattern (see `variations_key`) so distinct
+        // variable-font instances aren't collapsed together in the cache.
+        let key = (
+            attrs.style,
+            attrs.weight,
+            attrs.stretch,
+            variations_key(&attrs.variations),
+        );
         if let Some(f) = self.monospace_cache.read().get(&key) {
             return *f;
         }
@@ -168,6 +246,104 @@ impl FontSystem {
         None
     }
 
+    /// Find a loaded face that can actually render `ch`, preferring `attrs`' family and
+    /// style/weight/stretch, but falling back to any other loaded face that has a glyph
+    /// for it.
+    ///
+    /// This is the core of mixed-script text (emoji, CJK, symbols): without it, a caller
+    /// that hits a `.notdef` in its chosen face has to reimplement this search itself.
+    pub fn query_fallback(&self, ch: char, attrs: Attrs) -> Option<fontdb::ID> {
+        let font_attrs = FontAttrs {
+            family: vec![FamilyOwned::new(attrs.family)],
+            monospaced: attrs.monospaced,
+            stretch: attrs.stretch,
+            style: attrs.style,
+            weight: attrs.weight,
+            variations: variations_key(&attrs.variations),
+        };
+        let key = (ch, font_attrs);
+        if let Some(f) = self.fallback_cache.read().get(&key) {
+            return *f;
+        }
+        let (ch, font_attrs) = key;
+
+        // Collect candidates up front so we don't hold the database lock while calling
+        // `get_font`, which itself needs to take it to materialize shared face data.
+        let (preferred, candidate_ids): (Option<fontdb::ID>, Vec<fontdb::ID>) = {
+            let db = self.db.read();
+            let family: Vec<Family> = font_attrs.family.iter().map(|f| f.as_family()).collect();
+            let preferred = db.query(&Query {
+                families: &family,
+                style: attrs.style,
+                weight: attrs.weight,
+                stretch: attrs.stretch,
+            });
+            let ids = preferred
+                .into_iter()
+                .chain(db.faces().map(|face| face.id))
+                .collect();
+            (preferred, ids)
+        };
+
+        // The family's own face stays first, parsed or not. Faces already in `font_cache`
+        // cost nothing extra to check, so try all of them next; only a bounded number of
+        // other not-yet-parsed faces get force-parsed after that, so an uncommon codepoint
+        // can't make this walk the entire font collection.
+        let (already_parsed, not_yet_parsed): (Vec<fontdb::ID>, Vec<fontdb::ID>) = {
+            let font_cache = self.font_cache.read();
+            candidate_ids
+                .into_iter()
+                .partition(|id| Some(*id) == preferred || font_cache.contains_key(id))
+        };
+        let not_yet_parsed_len = not_yet_parsed.len();
+        let was_capped = not_yet_parsed_len > MAX_FALLBACK_FACES_TO_PARSE;
+        let candidate_ids: Vec<fontdb::ID> = already_parsed
+            .into_iter()
+            .chain(not_yet_parsed.into_iter().take(MAX_FALLBACK_FACES_TO_PARSE))
+            .collect();
+        if was_capped {
+            log::debug!(
+                "query_fallback for {:?} only parsed {} of {} remaining candidate faces",
+                ch,
+                MAX_FALLBACK_FACES_TO_PARSE,
+                not_yet_parsed_len
+            );
+        }
+
+        let mut fallback: Option<fontdb::ID> = None;
+        let mut result = None;
+        for candidate in candidate_ids {
+            let Some(font) = self.get_font(candidate) else {
+                continue;
+            };
+            if font.as_swash().charmap().map(ch) == 0 {
+                continue;
+            }
+
+            let matches_requested = self.db.read().face(candidate).is_some_and(|face| {
+                face.style == attrs.style
+                    && face.weight == attrs.weight
+                    && face.stretch == attrs.stretch
+            });
+            if matches_requested {
+                result = Some(candidate);
+                break;
+            }
+            fallback.get_or_insert(candidate);
+        }
+        let result = result.or(fallback);
+
+        // A capped scan didn't look at every candidate face, so a negative (or
+        // worse-than-ideal) result here isn't reliable enough to memoize forever --
+        // a face further down `db.faces()` that we didn't reach might actually cover
+        // `ch`. Only cache results from a scan that covered every candidate; capped
+        // lookups simply retry (and may see more of `font_cache` already warm) next time.
+        if !was_capped {
+            self.fallback_cache.write().insert((ch, font_attrs), result);
+        }
+        result
+    }
+
     pub fn face_name(&self, id: fontdb::ID) -> String {
         if let Some(face) = self.db.read().face(id) {
             if let Some((name, _)) = face.families.first() {
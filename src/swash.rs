@@ -9,7 +9,13 @@ use peniko::Color;
 use std::collections::HashMap as Map;
 use swash::scale::{image::Content, ScaleContext};
 use swash::scale::{Render, Source, StrikeWith};
-use swash::zeno::{Format, Vector};
+use swash::zeno::{Format, Transform, Vector};
+use swash::Tag;
+#[cfg(feature = "rayon")]
+use std::cell::RefCell;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 use crate::{CacheKey, FontSystem, FONT_SYSTEM};
 
@@ -18,6 +24,66 @@ pub use swash::zeno::{Command, Placement};
 
 const IS_MACOS: bool = cfg!(target_os = "macos");
 
+/// Selects how a glyph is rasterized: as a greyscale alpha coverage mask, or as a
+/// horizontal-RGB subpixel (LCD) coverage mask.
+///
+/// This mirrors the `FontRenderMode` distinction used by desktop compositors.
+/// Subpixel mode sharpens text on RGB-stripe LCD panels, but the caller must do
+/// component-wise alpha blending rather than treating the mask as a single alpha
+/// channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RenderMode {
+    /// Single-channel coverage mask, blended with a uniform color.
+    #[default]
+    Alpha,
+    /// Three-channel (R, G, B) coverage mask for LCD subpixel rendering.
+    Subpixel,
+}
+
+/// Describes how much a glyph needs to be faked up to match requested attrs that the
+/// matched font face doesn't actually provide, e.g. bold or italic requested against a
+/// family that only ships a regular, upright face.
+///
+/// Computed once at query time (see `FontSystem::face_synthesis`) and carried on the
+/// [`CacheKey`] so synthesized and real glyphs are rasterized, and cached, independently.
+///
+/// Stores `embolden`/`skew` as bit patterns rather than raw `f32`, so `Synthesis` (and
+/// in turn `CacheKey`) stays `Eq`/`Hash`-able.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Synthesis {
+    embolden_bits: u32,
+    skew_bits: u32,
+}
+
+impl Synthesis {
+    /// Additional synthetic emboldening to apply, on top of any platform default.
+    pub fn embolden(&self) -> f32 {
+        f32::from_bits(self.embolden_bits)
+    }
+
+    /// Horizontal shear to apply to fake an italic/oblique style, or `0.0` for none.
+    pub fn skew(&self) -> f32 {
+        f32::from_bits(self.skew_bits)
+    }
+}
+
+impl From<(f32, f32)> for Synthesis {
+    /// Build a `Synthesis` from `(embolden, skew)`.
+    fn from((embolden, skew): (f32, f32)) -> Self {
+        Self {
+            embolden_bits: embolden.to_bits(),
+            skew_bits: skew.to_bits(),
+        }
+    }
+}
+
+// One `ScaleContext` per rayon worker thread, so `rasterize_batch` can scale glyphs
+// concurrently without any locking: each thread only ever touches its own context.
+#[cfg(feature = "rayon")]
+thread_local! {
+    static RAYON_SCALE_CONTEXT: RefCell<ScaleContext> = RefCell::new(ScaleContext::new());
+}
+
 fn swash_image(context: &mut ScaleContext, cache_key: CacheKey) -> Option<SwashImage> {
     let font = match FONT_SYSTEM.get_font(cache_key.font_id) {
         Some(some) => some,
@@ -32,13 +98,36 @@ fn swash_image(context: &mut ScaleContext, cache_key: CacheKey) -> Option<SwashI
         .builder(font.as_swash())
         .size(cache_key.font_size as f32)
         .hint(!IS_MACOS)
+        // `CacheKey::variations` stores each value's bit pattern rather than a raw
+        // `f32`, so the key stays `Eq`/`Hash`-able; decode it back here.
+        .variations(
+            cache_key
+                .variations
+                .iter()
+                .map(|(tag, bits)| (Tag::new(tag), f32::from_bits(*bits))),
+        )
         .build();
 
     // Compute the fractional offset-- you'll likely want to quantize this
     // in a real renderer
     let offset = Vector::new(cache_key.x_bin.as_float(), cache_key.y_bin.as_float());
 
-    let embolden = if IS_MACOS { 0.2 } else { 0. };
+    let embolden = (if IS_MACOS { 0.2 } else { 0. }) + cache_key.synthesis.embolden();
+    // Fake an italic/oblique style by shearing the glyph horizontally when the
+    // matched face doesn't have one of its own.
+    let skew = cache_key.synthesis.skew();
+    let transform = (skew != 0.0).then(|| Transform {
+        xx: 1.0,
+        yx: 0.0,
+        xy: -skew,
+        yy: 1.0,
+        x: 0.0,
+        y: 0.0,
+    });
+    let format = match cache_key.render_mode {
+        RenderMode::Alpha => Format::Alpha,
+        RenderMode::Subpixel => Format::Subpixel,
+    };
     // Select our source order
     Render::new(&[
         // Color outline with the first palette
@@ -49,10 +138,11 @@ fn swash_image(context: &mut ScaleContext, cache_key: CacheKey) -> Option<SwashI
         Source::Outline,
     ])
     // Select a subpixel format
-    .format(Format::Alpha)
+    .format(format)
     // Apply the fractional offset
     .offset(offset)
     .embolden(embolden)
+    .transform(transform)
     // Render the image
     .render(&mut scaler, cache_key.glyph_id)
 }
@@ -76,6 +166,12 @@ fn swash_outline_commands(
     let mut scaler = context
         .builder(font.as_swash())
         .size(cache_key.font_size as f32)
+        .variations(
+            cache_key
+                .variations
+                .iter()
+                .map(|(tag, bits)| (Tag::new(tag), f32::from_bits(*bits))),
+        )
         .build();
 
     // Scale the outline
@@ -90,20 +186,173 @@ fn swash_outline_commands(
     Some(path.commands().collect())
 }
 
+/// Default glyph image cache budget, in bytes. Chosen to comfortably hold a page or two
+/// of rasterized glyphs, the way WebRender sizes its own glyph caches.
+const DEFAULT_IMAGE_CACHE_BUDGET: usize = 8 * 1024 * 1024;
+
+/// Approximate resident footprint of a cached rasterization result: the raw coverage
+/// bytes plus the placement metadata next to them. Failed (`None`) rasterizations are
+/// treated as free, so a glyph that's known to be broken is never re-attempted just
+/// because it was evicted.
+fn image_footprint(image: &Option<SwashImage>) -> usize {
+    match image {
+        None => 0,
+        Some(image) => image.data.len() + core::mem::size_of::<Placement>(),
+    }
+}
+
+/// Pixel bounding box and advance for a single glyph, computed without rasterizing it.
+///
+/// Mirrors WebRender's `GlyphDimensions`: layout and hit-testing code frequently only
+/// needs the box a glyph occupies, and forcing a full rasterization just to throw the
+/// image away is wasteful, especially for offscreen or clipped glyphs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlyphDimensions {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+    pub advance: f32,
+}
+
+fn swash_glyph_dimensions(
+    context: &mut ScaleContext,
+    cache_key: CacheKey,
+) -> Option<GlyphDimensions> {
+    let font = match FONT_SYSTEM.get_font(cache_key.font_id) {
+        Some(some) => some,
+        None => {
+            log::warn!("did not find font {:?}", cache_key.font_id);
+            return None;
+        }
+    };
+
+    let advance = font
+        .as_swash()
+        .glyph_metrics(&[])
+        .scale(cache_key.font_size as f32)
+        .advance_width(cache_key.glyph_id);
+
+    let mut scaler = context
+        .builder(font.as_swash())
+        .size(cache_key.font_size as f32)
+        .hint(!IS_MACOS)
+        .variations(
+            cache_key
+                .variations
+                .iter()
+                .map(|(tag, bits)| (Tag::new(tag), f32::from_bits(*bits))),
+        )
+        .build();
+
+    let outline = scaler
+        .scale_outline(cache_key.glyph_id)
+        .or_else(|| scaler.scale_color_outline(cache_key.glyph_id))?;
+    let mut bounds = outline.bounds();
+
+    // Match the embolden/skew `swash_image` actually rasterizes with, so the reported
+    // box lines up with the image for synthetically bolded/obliqued glyphs -- exactly
+    // the case where the naive outline bounds would be the most wrong.
+    let embolden = (if IS_MACOS { 0.2 } else { 0. }) + cache_key.synthesis.embolden();
+    bounds.min.x -= embolden;
+    bounds.min.y -= embolden;
+    bounds.max.x += embolden;
+    bounds.max.y += embolden;
+
+    let skew = cache_key.synthesis.skew();
+    if skew != 0.0 {
+        // Shear the same way `swash_image`'s `Transform` does and re-derive the x bounds
+        // from the sheared corners.
+        let sheared_min_x = bounds.min.x - skew * bounds.max.y;
+        let sheared_max_x = bounds.max.x - skew * bounds.min.y;
+        bounds.min.x = bounds.min.x.min(sheared_min_x);
+        bounds.max.x = bounds.max.x.max(sheared_max_x);
+    }
+
+    Some(GlyphDimensions {
+        left: bounds.min.x.floor() as i32,
+        top: bounds.max.y.ceil() as i32,
+        width: (bounds.max.x - bounds.min.x).max(0.0).ceil() as u32,
+        height: (bounds.max.y - bounds.min.y).max(0.0).ceil() as u32,
+        advance,
+    })
+}
+
 /// Cache for rasterizing with the swash scaler
 pub struct SwashCache {
     context: ScaleContext,
     pub image_cache: Map<CacheKey, Option<SwashImage>>,
     pub outline_command_cache: Map<CacheKey, Option<Vec<swash::zeno::Command>>>,
+    dimensions_cache: Map<CacheKey, Option<GlyphDimensions>>,
+    image_cache_budget: usize,
+    image_cache_bytes: usize,
+    // Logical clock bumped on every access; the entry with the smallest value is the
+    // least-recently-used one and the first to be evicted once over budget.
+    image_cache_access: Map<CacheKey, u64>,
+    image_cache_clock: u64,
 }
 
 impl SwashCache {
-    /// Create a new swash cache
+    /// Create a new swash cache, with the default glyph image cache budget
     pub fn new() -> Self {
+        Self::with_budget(DEFAULT_IMAGE_CACHE_BUDGET)
+    }
+
+    /// Create a new swash cache with a glyph image cache budget, in bytes
+    pub fn with_budget(budget_bytes: usize) -> Self {
         Self {
             context: ScaleContext::new(),
             image_cache: Map::new(),
             outline_command_cache: Map::new(),
+            dimensions_cache: Map::new(),
+            image_cache_budget: budget_bytes,
+            image_cache_bytes: 0,
+            image_cache_access: Map::new(),
+            image_cache_clock: 0,
+        }
+    }
+
+    /// Change the glyph image cache budget, in bytes, evicting least-recently-used
+    /// entries immediately if the new budget is smaller than what's currently cached
+    pub fn set_budget(&mut self, budget_bytes: usize) {
+        self.image_cache_budget = budget_bytes;
+        self.evict_image_cache();
+    }
+
+    /// Approximate number of bytes currently held by cached glyph images
+    pub fn memory_usage(&self) -> usize {
+        self.image_cache_bytes
+    }
+
+    fn touch_image_cache(&mut self, cache_key: CacheKey) {
+        // Failed rasterizations are cheap and must never be evicted, so a glyph that's
+        // known to be broken isn't re-attempted on its next lookup: keep them out of
+        // LRU tracking entirely rather than letting them be picked as the coldest entry.
+        match self.image_cache.get(&cache_key) {
+            Some(Some(_)) => {
+                self.image_cache_clock += 1;
+                self.image_cache_access.insert(cache_key, self.image_cache_clock);
+            }
+            _ => {
+                self.image_cache_access.remove(&cache_key);
+            }
+        }
+    }
+
+    fn evict_image_cache(&mut self) {
+        while self.image_cache_bytes > self.image_cache_budget {
+            let Some(&coldest_key) = self
+                .image_cache_access
+                .iter()
+                .min_by_key(|(_, &accessed_at)| accessed_at)
+                .map(|(key, _)| key)
+            else {
+                break;
+            };
+            self.image_cache_access.remove(&coldest_key);
+            if let Some(image) = self.image_cache.remove(&coldest_key) {
+                self.image_cache_bytes -= image_footprint(&image);
+            }
         }
     }
 
@@ -112,11 +361,80 @@ impl SwashCache {
         swash_image(&mut self.context, cache_key)
     }
 
+    /// Get a glyph's pixel bounding box and advance, without rasterizing it
+    ///
+    /// Caches the (much smaller) result separately from `image_cache`, so looking up
+    /// dimensions for layout or hit-testing doesn't force a full rasterization.
+    pub fn glyph_dimensions(&mut self, cache_key: CacheKey) -> Option<GlyphDimensions> {
+        *self
+            .dimensions_cache
+            .entry(cache_key)
+            .or_insert_with(|| swash_glyph_dimensions(&mut self.context, cache_key))
+    }
+
     /// Create a swash Image from a cache key, caching results
     pub fn get_image(&mut self, cache_key: CacheKey) -> &Option<SwashImage> {
-        self.image_cache
-            .entry(cache_key)
-            .or_insert_with(|| swash_image(&mut self.context, cache_key))
+        if !self.image_cache.contains_key(&cache_key) {
+            let image = swash_image(&mut self.context, cache_key);
+            self.image_cache_bytes += image_footprint(&image);
+            self.image_cache.insert(cache_key, image);
+            self.evict_image_cache();
+        }
+        self.touch_image_cache(cache_key);
+        self.image_cache.get(&cache_key).unwrap()
+    }
+
+    /// Rasterize many glyphs at once, spreading the work across a rayon thread pool.
+    ///
+    /// Each worker thread scales on its own thread-local [`ScaleContext`]
+    /// (`RAYON_SCALE_CONTEXT`), so fonts (already `Arc`-shared through [`FONT_SYSTEM`])
+    /// only need to be read concurrently. Keys already in `image_cache` (e.g. still
+    /// warm from the previous frame) are skipped rather than re-rasterized. Results are
+    /// merged into `image_cache` under a single write once every key has been
+    /// rasterized, mirroring how WebRender's glyph rasterizer fans work out across its
+    /// thread pool and joins it back.
+    #[cfg(feature = "rayon")]
+    pub fn rasterize_batch(&mut self, keys: &[CacheKey]) -> Vec<Option<SwashImage>> {
+        // The same glyph commonly repeats many times within one batch (e.g. a line of
+        // text), so dedup uncached keys: otherwise duplicates get rasterized twice in
+        // parallel and `image_cache_bytes` double-counts a footprint that only one map
+        // entry actually holds, permanently drifting the budget accounting upward.
+        let mut seen = std::collections::HashSet::new();
+        let to_rasterize: Vec<CacheKey> = keys
+            .iter()
+            .copied()
+            .filter(|cache_key| !self.image_cache.contains_key(cache_key) && seen.insert(*cache_key))
+            .collect();
+
+        let freshly_rasterized: Vec<(CacheKey, Option<SwashImage>)> = to_rasterize
+            .par_iter()
+            .map(|&cache_key| {
+                let image = RAYON_SCALE_CONTEXT
+                    .with(|context| swash_image(&mut context.borrow_mut(), cache_key));
+                (cache_key, image)
+            })
+            .collect();
+
+        for (cache_key, image) in freshly_rasterized {
+            self.image_cache_bytes += image_footprint(&image);
+            self.image_cache.insert(cache_key, image);
+        }
+
+        // Snapshot the results now, before eviction runs: a batch whose total footprint
+        // exceeds the budget is exactly what this API is for, and evicting first would
+        // let it reclaim entries this same call just produced, reporting them back as
+        // if rasterization had failed.
+        let results: Vec<Option<SwashImage>> = keys
+            .iter()
+            .map(|cache_key| self.image_cache.get(cache_key).cloned().flatten())
+            .collect();
+
+        for &cache_key in keys {
+            self.touch_image_cache(cache_key);
+        }
+        self.evict_image_cache();
+
+        results
     }
 
     pub fn get_outline_commands(
@@ -172,7 +490,20 @@ impl SwashCache {
                     }
                 }
                 Content::SubpixelMask => {
-                    log::warn!("TODO: SubpixelMask");
+                    let mut i = 0;
+                    for off_y in 0..image.placement.height as i32 {
+                        for off_x in 0..image.placement.width as i32 {
+                            let r = image.data[i];
+                            let g = image.data[i + 1];
+                            let b = image.data[i + 2];
+                            // There's no single alpha channel for a subpixel mask, so
+                            // fall back to the strongest of the three coverage channels;
+                            // callers doing proper LCD blending use r/g/b independently.
+                            let a = r.max(g).max(b);
+                            f(x + off_x, y + off_y, Color::rgba8(r, g, b, a));
+                            i += 4;
+                        }
+                    }
                 }
             }
         }